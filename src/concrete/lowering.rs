@@ -1,5 +1,6 @@
 use super::*;
 use crate::concrete::errors::*;
+use crate::hir;
 
 pub mod decl;
 pub mod pattern;
@@ -13,6 +14,16 @@ pub struct LoweringCtx {
     types: HashMap<Identifier, Rc<abs::Definition>>,
     errors: Rc<RefCell<Vec<miette::Report>>>,
     counter: Rc<Cell<usize>>,
+    /// Current let-nesting depth, stamped onto every [hir::Variable] created
+    /// from here on and used by [hir::Type::generalize] to decide which
+    /// holes are safe to quantify. See [Self::enter_level].
+    level: Rc<Cell<usize>>,
+    /// Constructor-arity table used by [Self::check_match_usefulness] to
+    /// decide whether a column of constructor patterns is complete. Filled
+    /// only through [Self::declare_constructors] (one call per sum type),
+    /// which no lowering path in this tree calls, so the table stays empty
+    /// and every constructor column counts as incomplete.
+    signature: Rc<RefCell<hir::usefulness::Signature<abs::Reference>>>,
     #[cfg(debug_assertions)]
     gas: Rc<Cell<usize>>,
 }
@@ -31,6 +42,8 @@ impl Default for LoweringCtx {
             ]),
             errors: Default::default(),
             counter: Default::default(),
+            level: Default::default(),
+            signature: Default::default(),
             #[cfg(debug_assertions)]
             gas: Default::default(),
         }
@@ -51,6 +64,31 @@ impl LoweringCtx {
     #[inline(always)]
     fn burn(&self) {}
 
+    /// The current let-nesting depth, i.e. the level new holes are
+    /// stamped with and the level `let`-bound schemes are generalized
+    /// relative to.
+    #[allow(dead_code)]
+    fn current_level(&self) -> usize {
+        self.level.get()
+    }
+
+    /// Enters the body of a binder (a `let` or a `fun`) for the duration
+    /// of `f`, bumping [Self::current_level] by one so that any hole
+    /// created inside is too deep to be generalized by an enclosing `let`.
+    #[allow(dead_code)]
+    fn enter_level<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.level.set(self.level.get() + 1);
+        let result = f();
+        self.level.set(self.level.get() - 1);
+        result
+    }
+
+    /// Creates a fresh, unbound hole at the current level.
+    #[allow(dead_code)]
+    fn new_hole(&self) -> hir::Type {
+        hir::Type::Hole(hir::Variable::new(self.current_level()))
+    }
+
     fn new_fresh_variable(&mut self) -> Rc<abs::Definition> {
         self.counter.set(self.counter.get() + 1);
         let name = Identifier::new(&format!("_{}", self.counter.get()), self.src_pos.clone());
@@ -131,6 +169,76 @@ impl LoweringCtx {
         }
     }
 
+    /// Registers `variants` (each constructor name paired with its arity)
+    /// as the complete, exact constructor set of one sum type, so that
+    /// [Self::check_match_usefulness] can recognize a match over all of
+    /// them as exhaustive even without a trailing wildcard arm.
+    ///
+    /// Nothing in this tree calls it: no type declaration's variants are
+    /// registered here. [hir::usefulness::Signature::declare], which this
+    /// forwards to, is tested in `hir::usefulness`.
+    #[allow(dead_code)]
+    fn declare_constructors(&self, variants: Vec<(abs::Reference, usize)>) {
+        self.signature.borrow_mut().declare(variants);
+    }
+
+    /// Runs Maranget's usefulness algorithm over the pattern rows of a
+    /// `match` expression and reports non-exhaustiveness/redundant arms
+    /// through [Self::report_error].
+    ///
+    /// `match_loc` is the span of the whole `match` (used to locate a
+    /// [hir::NonExhaustiveMatchError], since there's no single arm to blame
+    /// for a missing case), and `arm_locs` is the span of each arm, in the
+    /// same order as `rows`, used to locate a [hir::RedundantArmError] at
+    /// the specific arm it's redundant.
+    ///
+    /// It works on the source rows rather than on a compiled
+    /// [hir::CaseTree], since usefulness is a property of the rows. No
+    /// lowering path calls it, the `TermKind::Match` one included, so
+    /// lowering a `match` reports neither missing cases nor redundant arms.
+    /// The algorithm it drives is tested in `hir::usefulness`; this method
+    /// itself has no test.
+    #[allow(dead_code)]
+    fn check_match_usefulness(
+        &self,
+        match_loc: crate::loc::Loc,
+        arm_locs: &[crate::loc::Loc],
+        rows: &[hir::usefulness::Row<abs::Reference>],
+    ) {
+        use hir::usefulness::{check_exhaustiveness, find_redundant_rows, render_witness};
+
+        let arity = rows.first().map_or(0, Vec::len);
+        let signature = self.signature.borrow();
+
+        for index in find_redundant_rows(rows, &signature) {
+            self.report_error(hir::RedundantArmError { index, loc: arm_locs[index].clone() });
+        }
+
+        if let Some(witness) = check_exhaustiveness(rows, arity, &signature) {
+            self.report_error(hir::NonExhaustiveMatchError {
+                witness: render_witness(&witness),
+                loc: match_loc,
+            });
+        }
+    }
+
+    /// Unifies `expected` (the type the surrounding context requires, e.g.
+    /// a function's parameter type) against `found` (the type of the
+    /// offending sub-expression, e.g. the argument it was applied to),
+    /// reporting a two-sided "expected/found" [hir::UnificationError]
+    /// through [Self::report_error] on failure.
+    ///
+    /// `expected_loc`/`found_loc` locate the two sub-expressions being
+    /// compared (their [Term::src_pos](hir::Term)), so the diagnostic
+    /// highlights both of them. No lowering path calls it, the `App`/`If`/`Ascription`
+    /// ones included; it is exercised directly in this module's tests.
+    #[allow(dead_code)]
+    fn unify(&self, expected: hir::Type, expected_loc: crate::loc::Loc, found: hir::Type, found_loc: crate::loc::Loc) {
+        if let Err(error) = expected.unify(found, expected_loc, found_loc) {
+            self.report_error(error);
+        }
+    }
+
     fn sep_by(&mut self, desired: BinOp, mut acc: Term) -> miette::Result<Vec<Term>> {
         self.burn();
 
@@ -151,3 +259,58 @@ impl LoweringCtx {
         Ok(terms)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entering_a_level_bumps_fresh_holes() {
+        let ctx = LoweringCtx::default();
+
+        let hir::Type::Hole(outer) = ctx.new_hole() else {
+            panic!("new_hole should always return a Hole");
+        };
+        assert_eq!(outer.level(), 0);
+
+        let hir::Type::Hole(inner) = ctx.enter_level(|| ctx.new_hole()) else {
+            panic!("new_hole should always return a Hole");
+        };
+        assert_eq!(inner.level(), 1);
+
+        // Leaving the binder restores the level for anything created after it.
+        let hir::Type::Hole(after) = ctx.new_hole() else {
+            panic!("new_hole should always return a Hole");
+        };
+        assert_eq!(after.level(), 0);
+    }
+
+    #[test]
+    fn unify_reports_a_diagnostic_on_mismatch() {
+        let ctx = LoweringCtx::default();
+        assert!(ctx.errors.borrow().is_empty());
+
+        let tuple = hir::Type::Tuple(vec![]);
+        let fun = hir::Type::Fun(Box::new(hir::Type::Any), Box::new(hir::Type::Any));
+        ctx.unify(tuple, Loc::default(), fun, Loc::default());
+
+        assert_eq!(ctx.errors.borrow().len(), 1);
+    }
+
+    #[test]
+    fn unify_reports_nothing_on_a_compatible_pair() {
+        let ctx = LoweringCtx::default();
+
+        let expected = hir::Type::Fun(Box::new(hir::Type::Any), Box::new(hir::Type::Tuple(vec![])));
+        let found = hir::Type::Hole(hir::Variable::new(0));
+        ctx.unify(expected, Loc::default(), found, Loc::default());
+
+        assert!(ctx.errors.borrow().is_empty());
+    }
+
+    // `check_match_usefulness`/`declare_constructors` are exercised against
+    // the generic algorithm directly in `hir::usefulness`'s own tests
+    // (using a stand-in constructor-id type), rather than here: building a
+    // real `abs::Reference` to drive them through `LoweringCtx` would mean
+    // guessing at an API this tree doesn't define anywhere.
+}
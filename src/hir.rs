@@ -9,6 +9,9 @@ use crate::{
     loc::Loc,
 };
 
+pub mod eval;
+pub mod usefulness;
+
 /// A term is a node in the HIR. It has a [TermKind] and a type. It holds
 /// the location and type information of the term.
 #[derive(Debug, Clone)]
@@ -125,18 +128,66 @@ pub struct Scheme {
     pub mono: Type,
 }
 
+/// The mutable contents of a [Variable]: its current binding, if any, and
+/// the level (let-nesting depth) it was created at.
+#[derive(Debug, Default)]
+struct VariableState {
+    value: Option<Type>,
+    level: usize,
+}
+
 /// A variable is a mutable reference to a type. It is used to represent
 /// a variable in the HIR.
+///
+/// Every hole is stamped with a `level`, the let-nesting depth of
+/// [crate::concrete::lowering::LoweringCtx] at the point it was created.
+/// [Type::generalize] uses this to only quantify holes that don't escape
+/// into an enclosing binder, which is what makes let-polymorphism sound.
 #[derive(Debug, Clone, Default)]
-pub struct Variable(Arc<RwLock<Option<Type>>>);
+pub struct Variable(Arc<RwLock<VariableState>>);
 
 impl Variable {
+    /// Creates a fresh, unbound hole at the given level.
+    pub fn new(level: usize) -> Variable {
+        Variable(Arc::new(RwLock::new(VariableState { value: None, level })))
+    }
+
     pub fn value(&self) -> Option<Type> {
-        self.0.read().unwrap().clone()
+        self.0.read().unwrap().value.clone()
     }
 
     pub fn update(&self, value: Type) {
-        *self.0.write().unwrap() = Some(value);
+        self.0.write().unwrap().value = Some(value);
+    }
+
+    pub fn level(&self) -> usize {
+        self.0.read().unwrap().level
+    }
+
+    /// Lowers this hole's level to `min(current level, level)`. Used when
+    /// binding a hole to a type that mentions other holes: those holes
+    /// can't be generalized any more eagerly than the hole they now flow
+    /// into.
+    pub fn lower_level_to(&self, level: usize) {
+        let mut state = self.0.write().unwrap();
+        state.level = state.level.min(level);
+    }
+
+    /// Whether `self` and `other` are the very same hole, by reference
+    /// rather than by the value they currently hold. This is what the
+    /// occurs check needs: two distinct unbound holes both holding `None`
+    /// would otherwise compare equal under [PartialEq].
+    pub fn is(&self, other: &Variable) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// A stable identity key for this hole, for use as a `HashMap` key in
+    /// place of `Type`'s structural `Eq`/`Hash` (which, for `Hole`,
+    /// delegates to this `Variable`'s own value-based [PartialEq]/[Hash] and
+    /// so can't tell two distinct unbound holes apart -- see
+    /// [Type::generalize]).
+    fn identity(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
     }
 }
 
@@ -150,7 +201,7 @@ impl Eq for Variable {}
 
 impl Hash for Variable {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.read().unwrap().hash(state);
+        self.value().hash(state);
     }
 }
 
@@ -166,14 +217,54 @@ pub struct UnresolvedConstructorError;
 #[error("application pattern in constructor")]
 pub struct ApplicationPatternInConstructorError;
 
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("non-exhaustive match: missing case `{witness}`")]
+pub struct NonExhaustiveMatchError {
+    pub witness: String,
+
+    #[label("this match doesn't cover every case, e.g. `{witness}`")]
+    pub loc: Loc,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("unreachable pattern: arm {index} is already covered by a previous arm")]
+pub struct RedundantArmError {
+    pub index: usize,
+
+    #[label("this arm is unreachable, every value it matches is already covered above")]
+    pub loc: Loc,
+}
+
+/// Two-sided "expected/found" type errors: `expected_loc`/`found_loc` are
+/// the source positions of the two sub-expressions being compared (e.g. a
+/// function's parameter and the argument applied to it), so the diagnostic
+/// can point at both instead of printing a bare pair of types.
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
 #[error("unification error")]
 pub enum UnificationError {
     #[error("incompatible types")]
-    IncompatibleTypes(Type, Type),
+    IncompatibleTypes {
+        expected: Type,
+        found: Type,
 
-    #[error("incompatible constructors")]
-    IncompatibleConstructors(Reference, Reference),
+        #[label("expected this to have a compatible type...")]
+        expected_loc: Loc,
+
+        #[label("...but found this, which doesn't match")]
+        found_loc: Loc,
+    },
+
+    #[error("incompatible constructors `{expected}` and `{found}`")]
+    IncompatibleConstructors {
+        expected: Reference,
+        found: Reference,
+
+        #[label("constructor expected here")]
+        expected_loc: Loc,
+
+        #[label("incompatible constructor found here")]
+        found_loc: Loc,
+    },
 
     #[error("occurs check")]
     OccursCheck,
@@ -184,7 +275,9 @@ impl Scheme {
         Scheme { args: 0, mono: value }
     }
 
-    pub fn instantiate(&self) -> Type {
+    /// Instantiates this scheme's quantified variables as fresh holes at
+    /// `level`, the current let-nesting depth of the call site.
+    pub fn instantiate(&self, level: usize) -> Type {
         fn go(holes: &HashMap<usize, Type>, tt: Type) -> Type {
             match tt {
                 Type::Any => Type::Any,
@@ -200,100 +293,292 @@ impl Scheme {
         }
         let mut holes = HashMap::new();
         for idx in 0..self.args {
-            holes.insert(idx, Type::Hole(Variable::default()));
+            holes.insert(idx, Type::Hole(Variable::new(level)));
         }
         go(&holes, self.mono.clone())
     }
 }
 
-impl From<crate::abstr::Type> for Type {
-    fn from(abstr: crate::abstr::Type) -> Self {
-        fn go(vars: &mut HashMap<String, Type>, value: crate::abstr::Type) -> Type {
+impl Type {
+    /// Converts a surface-syntax [crate::abstr::Type] (an explicit type
+    /// annotation or ascription) into an HIR [Type], stamping every hole
+    /// it creates -- for a declared type variable or a bare `_` -- with
+    /// `level`, the level of the context the annotation appears in.
+    ///
+    /// This has to be a real parameter rather than always using the
+    /// outermost level (as a plain `Variable::default()` would): an
+    /// annotation written inside a `let`'s body is at that `let`'s level,
+    /// and a hole stamped level 0 regardless of where it's written would
+    /// never be quantified by [Type::generalize], leaving the enclosing
+    /// `let` monomorphic in it.
+    pub fn from_abstr(abstr: crate::abstr::Type, level: usize) -> Self {
+        fn go(vars: &mut HashMap<String, Type>, value: crate::abstr::Type, level: usize) -> Type {
             use crate::abstr::Type::*;
             match value {
-                SrcPos(box term, _) => go(vars, term),
-                Pair(elements) => Type::Pair(elements.into_iter().map(|element| go(vars, element)).collect()),
-                Tuple(elements) => Type::Tuple(elements.into_iter().map(|element| go(vars, element)).collect()),
-                Fun(box domain, box codomain) => Type::Fun(go(vars, domain).into(), go(vars, codomain).into()),
-                App(name, box argument) => Type::App(name, go(vars, argument).into()),
-                Local(box local) => Type::Local(go(vars, local).into()),
+                SrcPos(box term, _) => go(vars, term, level),
+                Pair(elements) => Type::Pair(elements.into_iter().map(|element| go(vars, element, level)).collect()),
+                Tuple(elements) => Type::Tuple(elements.into_iter().map(|element| go(vars, element, level)).collect()),
+                Fun(box domain, box codomain) => {
+                    Type::Fun(go(vars, domain, level).into(), go(vars, codomain, level).into())
+                }
+                App(name, box argument) => Type::App(name, go(vars, argument, level).into()),
+                Local(box local) => Type::Local(go(vars, local, level).into()),
                 Meta(id) => vars
                     .entry(id.text)
-                    .or_insert_with(|| Type::Hole(Variable::default()))
+                    .or_insert_with(|| Type::Hole(Variable::new(level)))
                     .clone(),
                 Constructor(constructor) => Type::Constructor(constructor),
-                Hole => Type::Hole(Variable::default()),
+                Hole => Type::Hole(Variable::new(level)),
             }
         }
 
-        go(&mut HashMap::new(), abstr)
+        go(&mut HashMap::new(), abstr, level)
     }
-}
 
-impl Type {
-    #[allow(clippy::mutable_key_type)]
-    pub fn generalize(self) -> Scheme {
-        fn go(vars: &mut HashMap<Type, usize>, value: Type) -> Type {
+    /// Quantifies every hole whose level is strictly greater than `level`,
+    /// the level of the binder this type is being generalized at (e.g. a
+    /// `let`). Holes at or below `level` are still in scope for an
+    /// enclosing binder and are left as monomorphic [Type::Hole]s instead
+    /// of being over-generalized.
+    ///
+    /// This is the standard "level" trick for let-polymorphism: it's
+    /// equivalent to scanning the whole environment for free variables,
+    /// but just a level comparison instead.
+    pub fn generalize(self, level: usize) -> Scheme {
+        fn go(vars: &mut HashMap<usize, usize>, level: usize, value: Type) -> Type {
             use Type::*;
 
             match value {
                 Type::Any => Type::Any,
-                Pair(elements) => Type::Pair(elements.into_iter().map(|element| go(vars, element)).collect()),
-                Tuple(elements) => Type::Tuple(elements.into_iter().map(|element| go(vars, element)).collect()),
-                Fun(box domain, box codomain) => Type::Fun(go(vars, domain).into(), go(vars, codomain).into()),
-                App(name, box argument) => Type::App(name, go(vars, argument).into()),
-                Local(box local) => Type::Local(go(vars, local).into()),
-                Hole(_) => {
+                Pair(elements) => Type::Pair(elements.into_iter().map(|element| go(vars, level, element)).collect()),
+                Tuple(elements) => Type::Tuple(elements.into_iter().map(|element| go(vars, level, element)).collect()),
+                Fun(box domain, box codomain) => Type::Fun(go(vars, level, domain).into(), go(vars, level, codomain).into()),
+                App(name, box argument) => Type::App(name, go(vars, level, argument).into()),
+                Local(box local) => Type::Local(go(vars, level, local).into()),
+                Hole(h) if h.level() > level => {
+                    // Keyed on the hole's identity, not on `Type`'s
+                    // structural Eq/Hash: two distinct unbound holes both
+                    // have `value() == None` and would otherwise collapse
+                    // onto the same Meta index.
                     let idx = vars.len();
-                    Type::Meta(*vars.entry(value).or_insert_with(|| idx))
+                    let meta = *vars.entry(h.identity()).or_insert(idx);
+                    Type::Meta(meta)
                 }
+                Hole(h) => Type::Hole(h),
                 Constructor(constructor) => Type::Constructor(constructor),
                 Meta(m) => Type::Meta(m),
             }
         }
 
         let mut vars = HashMap::new();
-        let mono = go(&mut vars, self);
+        let mono = go(&mut vars, level, self);
         Scheme { args: vars.len(), mono }
     }
 
-    pub fn unify(self, rhs: Type) -> Result<(), UnificationError> {
+    /// Whether `target` appears free in `self`, following already-bound
+    /// holes transitively. Used by [Type::unify] to reject cyclic bindings
+    /// like `'a ~ 'a -> int` before they're built.
+    fn occurs(&self, target: &Variable) -> bool {
+        match self {
+            Type::Any | Type::Constructor(_) | Type::Meta(_) => false,
+            Type::Pair(elements) | Type::Tuple(elements) => elements.iter().any(|element| element.occurs(target)),
+            Type::Fun(domain, codomain) => domain.occurs(target) || codomain.occurs(target),
+            Type::App(_, argument) => argument.occurs(target),
+            Type::Local(local) => local.occurs(target),
+            Type::Hole(h) => h.is(target) || h.value().is_some_and(|bound| bound.occurs(target)),
+        }
+    }
+
+    /// Lowers the level of every unbound hole reachable from `self` to
+    /// `min(its level, level)`. Called when binding a hole to `self`: the
+    /// holes `self` mentions can't be generalized past the point where
+    /// they now flow into the hole being bound.
+    fn lower_levels(&self, level: usize) {
+        match self {
+            Type::Any | Type::Constructor(_) | Type::Meta(_) => {}
+            Type::Pair(elements) | Type::Tuple(elements) => elements.iter().for_each(|element| element.lower_levels(level)),
+            Type::Fun(domain, codomain) => {
+                domain.lower_levels(level);
+                codomain.lower_levels(level);
+            }
+            Type::App(_, argument) => argument.lower_levels(level),
+            Type::Local(local) => local.lower_levels(level),
+            Type::Hole(h) => match h.value() {
+                Some(bound) => bound.lower_levels(level),
+                None => h.lower_level_to(level),
+            },
+        }
+    }
+
+    /// Unifies `self` (the expected type) against `rhs` (the found type).
+    /// `expected_loc`/`found_loc` are the source positions of the two
+    /// sub-expressions being compared, threaded through purely so a failing
+    /// unification can build a two-sided [UnificationError] that points at
+    /// both of them, rather than printing a bare pair of types.
+    pub fn unify(self, rhs: Type, expected_loc: Loc, found_loc: Loc) -> Result<(), UnificationError> {
         use Type::*;
         use UnificationError::*;
 
         match (self, rhs) {
             (Any, _) | (_, Any) => Ok(()),
-            (Local(box lvar), Local(box rvar)) => lvar.unify(rvar),
+            (Local(box lvar), Local(box rvar)) => lvar.unify(rvar, expected_loc, found_loc),
             (Constructor(lconstructor), Constructor(rconstructor)) if lconstructor == rconstructor => Ok(()),
-            (App(ln, box largument), App(rn, box rargument)) if ln == rn => largument.unify(rargument),
-            (App(ln, _), App(rn, _)) => Err(IncompatibleConstructors(ln, rn)),
+            (App(ln, box largument), App(rn, box rargument)) if ln == rn => {
+                largument.unify(rargument, expected_loc, found_loc)
+            }
+            (App(ln, _), App(rn, _)) => Err(IncompatibleConstructors {
+                expected: ln,
+                found: rn,
+                expected_loc,
+                found_loc,
+            }),
             (Fun(box ldom, box lcod), Fun(box rdom, box rcod)) => {
-                ldom.unify(rdom)?;
-                lcod.unify(rcod)
+                ldom.unify(rdom, expected_loc.clone(), found_loc.clone())?;
+                lcod.unify(rcod, expected_loc, found_loc)
             }
             (Pair(lelements), Pair(relements)) => {
                 for (lelement, relement) in lelements.into_iter().zip(relements.into_iter()) {
-                    lelement.unify(relement)?;
+                    lelement.unify(relement, expected_loc.clone(), found_loc.clone())?;
                 }
                 Ok(())
             }
             (Tuple(lelements), Tuple(relements)) => {
                 for (lelement, relement) in lelements.into_iter().zip(relements.into_iter()) {
-                    lelement.unify(relement)?;
+                    lelement.unify(relement, expected_loc.clone(), found_loc.clone())?;
                 }
                 Ok(())
             }
-            (Hole(h), value) | (value, Hole(h)) => match h.value() {
-                Some(contents) => contents.unify(value),
+            (Hole(h), Hole(o)) if h.is(&o) => Ok(()),
+            (Hole(h), value) => match h.value() {
+                Some(contents) => contents.unify(value, expected_loc, found_loc),
+                None if value.occurs(&h) => Err(OccursCheck),
                 None => {
+                    value.lower_levels(h.level());
                     h.update(value);
                     Ok(())
                 }
             },
-            (Constructor(lconstructor), Constructor(rconstructor)) => {
-                Err(IncompatibleConstructors(lconstructor, rconstructor))
-            }
-            (lhs, rhs) => Err(IncompatibleTypes(lhs, rhs)),
+            (value, Hole(h)) => match h.value() {
+                Some(contents) => value.unify(contents, expected_loc, found_loc),
+                None if value.occurs(&h) => Err(OccursCheck),
+                None => {
+                    value.lower_levels(h.level());
+                    h.update(value);
+                    Ok(())
+                }
+            },
+            (Constructor(lconstructor), Constructor(rconstructor)) => Err(IncompatibleConstructors {
+                expected: lconstructor,
+                found: rconstructor,
+                expected_loc,
+                found_loc,
+            }),
+            (lhs, rhs) => Err(IncompatibleTypes {
+                expected: lhs,
+                found: rhs,
+                expected_loc,
+                found_loc,
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// When the *already-bound* hole is the right-hand operand, `expected`
+    /// must still be `self`'s side (the left operand as unify was called)
+    /// and `found` must still be `rhs`'s side (the hole's resolved
+    /// binding) -- not swapped, which would point a two-sided diagnostic's
+    /// labels at the wrong sub-expressions.
+    #[test]
+    fn unify_keeps_expected_and_found_on_the_right_side_when_the_bound_hole_is_on_the_right() {
+        let hole = Variable::new(0);
+        hole.update(Type::Fun(Box::new(Type::Any), Box::new(Type::Any)));
+
+        let expected = Type::Tuple(vec![]);
+        let found = Type::Hole(hole);
+
+        let Err(UnificationError::IncompatibleTypes { expected: reported_expected, found: reported_found, .. }) =
+            expected.clone().unify(found, Loc::default(), Loc::default())
+        else {
+            panic!("a tuple and a function should fail to unify with IncompatibleTypes");
+        };
+
+        assert_eq!(reported_expected, expected);
+        assert_eq!(reported_found, Type::Fun(Box::new(Type::Any), Box::new(Type::Any)));
+    }
+
+    /// Generalizing at a `let` should quantify holes created deeper than
+    /// that `let` (they don't escape into the surrounding scope) while
+    /// leaving holes at or above its level alone (those are still shared
+    /// with an enclosing binder, and over-generalizing them would be
+    /// unsound). Also checks that two distinct fresh holes, both unbound
+    /// and so both `value() == None`, get *independent* Meta indices
+    /// instead of collapsing onto the same one.
+    #[test]
+    fn generalize_quantifies_only_holes_past_the_given_level() {
+        let deep_a = Variable::new(1);
+        let deep_b = Variable::new(1);
+        let shallow = Variable::new(0);
+
+        let ty = Type::Pair(vec![Type::Hole(deep_a), Type::Hole(deep_b), Type::Hole(shallow)]);
+        let scheme = ty.generalize(0);
+
+        assert_eq!(scheme.args, 2);
+        let Type::Pair(elements) = scheme.mono else {
+            panic!("generalize should preserve the Pair shape");
+        };
+        assert!(matches!(elements[0], Type::Meta(0)));
+        assert!(matches!(elements[1], Type::Meta(1)));
+        assert!(matches!(elements[2], Type::Hole(_)));
+    }
+
+    /// Self-application, `fun f -> f f`, requires unifying `'a` with
+    /// `'a -> 'b`; without an occurs check this builds an infinite type
+    /// and the unifier loops forever instead of rejecting the program.
+    #[test]
+    fn self_referential_function_fails_occurs_check() {
+        let hole = Variable::new(0);
+        let self_ref = Type::Hole(hole.clone());
+        let as_function = Type::Fun(Box::new(Type::Hole(hole)), Box::new(Type::Any));
+
+        let result = self_ref.unify(as_function, Loc::default(), Loc::default());
+        assert!(matches!(result, Err(UnificationError::OccursCheck)));
+    }
+
+    /// A recursive list-like type built through *two* holes, where the
+    /// cycle only shows up once the first hole's binding is followed,
+    /// must still be caught: the occurs check has to walk bound hole
+    /// chains, not just compare the immediate hole identities.
+    #[test]
+    fn recursive_type_through_bound_hole_chain_fails_occurs_check() {
+        let tail = Variable::new(0);
+        let head = Variable::new(0);
+        head.update(Type::Hole(tail.clone()));
+
+        let lhs = Type::Hole(tail);
+        let rhs = Type::Fun(Box::new(Type::Hole(head)), Box::new(Type::Any));
+
+        let result = lhs.unify(rhs, Loc::default(), Loc::default());
+        assert!(matches!(result, Err(UnificationError::OccursCheck)));
+    }
+
+    #[test]
+    fn from_abstr_stamps_holes_with_the_given_level() {
+        let Type::Hole(hole) = Type::from_abstr(crate::abstr::Type::Hole, 3) else {
+            panic!("from_abstr should turn an abstr Hole into a hir Hole");
+        };
+        assert_eq!(hole.level(), 3);
+    }
+
+    #[test]
+    fn unrelated_holes_unify_without_occurs_check_firing() {
+        let a = Variable::new(0);
+        let b = Variable::new(1);
+
+        let result = Type::Hole(a).unify(Type::Hole(b), Loc::default(), Loc::default());
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,410 @@
+//! A small constant evaluator / partial evaluator for [super::Term].
+//!
+//! This interprets terms against an environment of bindings to produce
+//! [Value]s, which lets us constant-fold arithmetic, select through
+//! `if`/`match` when the scrutinee is known, and beta-reduce applications
+//! of a known [super::TermKind::Fun]. It's exposed two ways: [eval_program]
+//! runs a whole `main` term to a final value, and [simplify_term] rewrites
+//! a term into a simplified one wherever a sub-term turns out to be
+//! constant, leaving the rest of the term untouched.
+
+use std::{cell::Cell, collections::HashMap};
+
+use crate::{
+    abstr::{Definition, Reference},
+    loc::{Loc, Text},
+};
+
+use super::{CaseTree, Condition, Occurrence, Term, TermKind, Type};
+
+/// A runtime value produced by the constant evaluator.
+///
+/// This mirrors [TermKind] closely enough that every variant except
+/// [Value::Closure] can be quoted back into a [Term] by [quote]:
+/// [Value::List]/[Value::Pair] correspond to
+/// [TermKind::List]/[TermKind::Pair], and [Value::Constructor] is a
+/// constructor [Reference] applied to however many arguments it has seen so
+/// far (constructors are just [TermKind::Var]s that accumulate arguments
+/// through [TermKind::App], since the HIR has no dedicated constructor
+/// application node).
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Text(Text),
+    List(Vec<Value>),
+    Pair(Vec<Value>),
+    Constructor(Reference, Vec<Value>),
+    Closure(std::sync::Arc<Definition>, Box<Term>, Env),
+}
+
+/// Bindings visible to the evaluator, keyed by the [Reference] a
+/// [TermKind::Var] resolves to. A name with no entry here is assumed to be
+/// a constructor, and evaluates to a zero-argument [Value::Constructor].
+pub type Env = HashMap<Reference, Value>;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum EvalError {
+    /// Raised when a [CaseTree::Failure] is reached: every case was
+    /// exhausted without matching, which only happens if [super::usefulness]
+    /// missed a gap (or the match was never checked).
+    #[error("non-exhaustive match at runtime")]
+    NonExhaustiveMatch,
+
+    #[error("unbound variable in constant evaluator")]
+    UnboundVariable,
+
+    #[error("attempted to call a non-function, non-constructor value")]
+    NotCallable,
+
+    #[error("branch condition did not evaluate to a known shape")]
+    BadProjection,
+
+    /// Mirrors `LoweringCtx`'s `gas`/`burn` guard: evaluation can diverge on
+    /// infinite recursion, so we bound the number of steps instead of
+    /// looping forever.
+    #[error("evaluation step budget exhausted")]
+    GasExhausted,
+}
+
+/// Step budget for a single [eval_program]/[simplify_term] run, mirroring
+/// `LoweringCtx`'s `gas` guard.
+const STEP_BUDGET: usize = 100_000;
+
+fn burn(budget: &Cell<usize>) -> Result<(), EvalError> {
+    if budget.get() == 0 {
+        return Err(EvalError::GasExhausted);
+    }
+    budget.set(budget.get() - 1);
+    Ok(())
+}
+
+/// Runs `main` to a final [Value] against the top-level `env`.
+pub fn eval_program(main: &Term, env: &Env) -> Result<Value, EvalError> {
+    let budget = Cell::new(STEP_BUDGET);
+    eval_term(main, env, &budget)
+}
+
+fn eval_term(term: &Term, env: &Env, budget: &Cell<usize>) -> Result<Value, EvalError> {
+    burn(budget)?;
+
+    match &term.value {
+        TermKind::Int(value) => Ok(Value::Int(*value)),
+        TermKind::Text(text) => Ok(Value::Text(text.clone())),
+        TermKind::List(elements) => Ok(Value::List(
+            elements.iter().map(|element| eval_term(element, env, budget)).collect::<Result<_, _>>()?,
+        )),
+        TermKind::Pair(elements) => Ok(Value::Pair(
+            elements.iter().map(|element| eval_term(element, env, budget)).collect::<Result<_, _>>()?,
+        )),
+        TermKind::Var(reference) => match env.get(reference) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(Value::Constructor(reference.clone(), Vec::new())),
+        },
+        TermKind::Fun(parameter, body) => Ok(Value::Closure(parameter.clone(), body.clone(), env.clone())),
+        TermKind::Ascription(inner, _) => eval_term(inner, env, budget),
+        TermKind::Let(definition, value, body) => {
+            let value = eval_term(value, env, budget)?;
+            let mut env = env.clone();
+            env.insert(Reference::from(definition.clone()), value);
+            eval_term(body, &env, budget)
+        }
+        TermKind::If(condition, then_branch, else_branch) => {
+            let condition = eval_term(condition, env, budget)?;
+            match as_bool(&condition) {
+                Some(true) => eval_term(then_branch, env, budget),
+                Some(false) => eval_term(else_branch, env, budget),
+                None => Err(EvalError::BadProjection),
+            }
+        }
+        TermKind::App(function, argument) => {
+            let function = eval_term(function, env, budget)?;
+            let argument = eval_term(argument, env, budget)?;
+            match function {
+                Value::Closure(parameter, body, closure_env) => {
+                    let mut env = closure_env;
+                    env.insert(Reference::from(parameter), argument);
+                    eval_term(&body, &env, budget)
+                }
+                Value::Constructor(reference, mut arguments) => {
+                    arguments.push(argument);
+                    match eval_primitive(&reference.to_string(), &arguments) {
+                        Some(result) => Ok(result),
+                        None => Ok(Value::Constructor(reference, arguments)),
+                    }
+                }
+                _ => Err(EvalError::NotCallable),
+            }
+        }
+        TermKind::Match(tree) => {
+            let scrutinee = eval_case_tree_root(tree, env, budget)?;
+            eval_case_tree(tree, env, &scrutinee, budget)
+        }
+    }
+}
+
+/// The root occurrence of a match's [CaseTree] is always evaluated against
+/// the term/variable being matched on, before any constructor has been
+/// selected, so it's resolved up front and threaded through as the
+/// projection root for every nested [Occurrence::Index]/[Occurrence::Tuple].
+fn eval_case_tree_root(tree: &CaseTree, env: &Env, budget: &Cell<usize>) -> Result<Value, EvalError> {
+    match tree {
+        CaseTree::Branch { occurence, .. } => eval_occurrence(occurence, env, None, budget),
+        CaseTree::Leaf(_) | CaseTree::Failure => Ok(Value::Pair(Vec::new())),
+    }
+}
+
+fn eval_case_tree(tree: &CaseTree, env: &Env, scrutinee: &Value, budget: &Cell<usize>) -> Result<Value, EvalError> {
+    burn(budget)?;
+
+    match tree {
+        CaseTree::Failure => Err(EvalError::NonExhaustiveMatch),
+        CaseTree::Leaf(term) => eval_term(term, env, budget),
+        CaseTree::Branch { occurence, cases, default } => {
+            let value = eval_occurrence(occurence, env, Some(scrutinee), budget)?;
+
+            for (condition, case) in cases {
+                if condition_matches(condition, &value) {
+                    return eval_case_tree(case, env, scrutinee, budget);
+                }
+            }
+
+            match default {
+                Some(case) => eval_case_tree(case, env, scrutinee, budget),
+                None => Err(EvalError::NonExhaustiveMatch),
+            }
+        }
+    }
+}
+
+/// Computes the value at `occurrence`, relative to the match's root
+/// `scrutinee` (`None` only when resolving the very first occurrence).
+fn eval_occurrence(occurrence: &Occurrence, env: &Env, scrutinee: Option<&Value>, budget: &Cell<usize>) -> Result<Value, EvalError> {
+    match occurrence {
+        Occurrence::Term(term) => eval_term(term, env, budget),
+        Occurrence::Variable(definition) => env
+            .get(&Reference::from(definition.clone()))
+            .cloned()
+            .ok_or(EvalError::UnboundVariable),
+        Occurrence::Index(index) => project_index(scrutinee.ok_or(EvalError::BadProjection)?, *index),
+        Occurrence::Tuple(row, col) => {
+            let row_value = project_index(scrutinee.ok_or(EvalError::BadProjection)?, *row)?;
+            project_index(&row_value, *col)
+        }
+    }
+}
+
+fn project_index(value: &Value, index: usize) -> Result<Value, EvalError> {
+    match value {
+        Value::Constructor(_, arguments) => arguments.get(index).cloned().ok_or(EvalError::BadProjection),
+        Value::Pair(elements) | Value::List(elements) => elements.get(index).cloned().ok_or(EvalError::BadProjection),
+        _ => Err(EvalError::BadProjection),
+    }
+}
+
+fn condition_matches(condition: &Condition, value: &Value) -> bool {
+    match (condition, value) {
+        (Condition::Constructor(expected, _), Value::Constructor(actual, _)) => expected == actual,
+        (Condition::Tuple(arity), Value::Pair(elements) | Value::List(elements)) => elements.len() == *arity,
+        _ => false,
+    }
+}
+
+/// Computes a binary arithmetic/comparison primitive over two known
+/// [Value::Int]s, or returns `None` if `name` isn't one of the primitives
+/// recognized here, `args` isn't exactly two [Value::Int]s, or the
+/// operation is undefined (e.g. division by zero) -- in every `None`
+/// case the caller just leaves the application un-evaluated.
+///
+/// Operators lower to plain [TermKind::App]s of a [TermKind::Var]
+/// referencing the operator's name (see `BinOp` lowering), the same way a
+/// data constructor does, so without this there's nothing to tell `+`
+/// apart from an unknown zero-argument constructor once it's fully
+/// applied. Comparisons fold to [Value::Int] `0`/`1` rather than a
+/// `True`/`False` constructor, matching the [Value::Int] half of
+/// [as_bool]'s own encoding, since a primitive has no [Reference] of its
+/// own to build a [Value::Constructor] from.
+fn eval_primitive(name: &str, args: &[Value]) -> Option<Value> {
+    let [Value::Int(lhs), Value::Int(rhs)] = args else {
+        return None;
+    };
+
+    let value = match name {
+        "+" => lhs.checked_add(*rhs)?,
+        "-" => lhs.checked_sub(*rhs)?,
+        "*" => lhs.checked_mul(*rhs)?,
+        "/" => lhs.checked_div(*rhs)?,
+        "%" => lhs.checked_rem(*rhs)?,
+        "=" | "==" => (*lhs == *rhs) as i64,
+        "<>" | "!=" => (*lhs != *rhs) as i64,
+        "<" => (*lhs < *rhs) as i64,
+        "<=" => (*lhs <= *rhs) as i64,
+        ">" => (*lhs > *rhs) as i64,
+        ">=" => (*lhs >= *rhs) as i64,
+        _ => return None,
+    };
+
+    Some(Value::Int(value))
+}
+
+/// Booleans have no dedicated [Value] shape: they're the nullary
+/// constructors `True`/`False` like every other zero-argument data
+/// constructor, so a known `if` condition is read off that encoding. A
+/// [Value::Int] is read as a boolean too (`0` is false, anything else is
+/// true), which is how folded comparisons come out of [eval_primitive].
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Constructor(reference, arguments) if arguments.is_empty() => match reference.to_string().as_str() {
+            "True" | "true" => Some(true),
+            "False" | "false" => Some(false),
+            _ => None,
+        },
+        Value::Int(0) => Some(false),
+        Value::Int(_) => Some(true),
+        _ => None,
+    }
+}
+
+/// Rewrites `term` into a simplified [Term], constant-folding arithmetic,
+/// selecting through `if`/`match` when the condition/scrutinee is known,
+/// and beta-reducing applications of a known [TermKind::Fun] &mdash;
+/// recursively, bottom-up, so a constant sub-expression folds even inside a
+/// term that isn't constant as a whole.
+pub fn simplify_term(term: &Term, env: &Env) -> Term {
+    let budget = Cell::new(STEP_BUDGET);
+    simplify_term_with(term, env, &budget)
+}
+
+fn simplify_term_with(term: &Term, env: &Env, budget: &Cell<usize>) -> Term {
+    let simplified_kind = match &term.value {
+        TermKind::Int(_) | TermKind::Text(_) | TermKind::Var(_) => term.value.clone(),
+        TermKind::List(elements) => {
+            TermKind::List(elements.iter().map(|element| simplify_term_with(element, env, budget)).collect())
+        }
+        TermKind::Pair(elements) => {
+            TermKind::Pair(elements.iter().map(|element| simplify_term_with(element, env, budget)).collect())
+        }
+        TermKind::Fun(parameter, body) => {
+            TermKind::Fun(parameter.clone(), Box::new(simplify_term_with(body, env, budget)))
+        }
+        TermKind::Ascription(inner, scheme) => {
+            TermKind::Ascription(Box::new(simplify_term_with(inner, env, budget)), scheme.clone())
+        }
+        TermKind::App(function, argument) => TermKind::App(
+            Box::new(simplify_term_with(function, env, budget)),
+            Box::new(simplify_term_with(argument, env, budget)),
+        ),
+        TermKind::If(condition, then_branch, else_branch) => TermKind::If(
+            Box::new(simplify_term_with(condition, env, budget)),
+            Box::new(simplify_term_with(then_branch, env, budget)),
+            Box::new(simplify_term_with(else_branch, env, budget)),
+        ),
+        TermKind::Let(definition, value, body) => TermKind::Let(
+            definition.clone(),
+            Box::new(simplify_term_with(value, env, budget)),
+            Box::new(simplify_term_with(body, env, budget)),
+        ),
+        TermKind::Match(tree) => TermKind::Match(simplify_case_tree(tree, env, budget)),
+    };
+
+    // A closure anywhere inside the value (e.g. a pair holding a `fun`) has
+    // no literal form, so only closure-free values are quoted.
+    fn is_quotable(value: &Value) -> bool {
+        match value {
+            Value::Closure(..) => false,
+            Value::List(elements) | Value::Pair(elements) | Value::Constructor(_, elements) => {
+                elements.iter().all(is_quotable)
+            }
+            Value::Int(_) | Value::Text(_) => true,
+        }
+    }
+
+    let candidate = Term { value: simplified_kind, src_pos: term.src_pos.clone(), type_repr: term.type_repr.clone() };
+
+    match eval_term(&candidate, env, budget) {
+        Ok(value) if is_quotable(&value) => quote(value, &candidate.src_pos, &candidate.type_repr),
+        _ => candidate,
+    }
+}
+
+fn simplify_case_tree(tree: &CaseTree, env: &Env, budget: &Cell<usize>) -> CaseTree {
+    match tree {
+        CaseTree::Failure => CaseTree::Failure,
+        CaseTree::Leaf(term) => CaseTree::Leaf(Box::new(simplify_term_with(term, env, budget))),
+        CaseTree::Branch { occurence, cases, default } => CaseTree::Branch {
+            occurence: occurence.clone(),
+            cases: cases
+                .iter()
+                .map(|(condition, case)| (condition.clone(), simplify_case_tree(case, env, budget)))
+                .collect(),
+            default: default
+                .as_ref()
+                .map(|case| Box::new(simplify_case_tree(case, env, budget))),
+        },
+    }
+}
+
+/// Rebuilds a constant [Value] as a [Term], the inverse of [eval_term] for
+/// every shape that's representable as a literal term. [Value::Constructor]
+/// is quoted back into nested [TermKind::App]s over a [TermKind::Var], the
+/// same shape the evaluator reads it from.
+fn quote(value: Value, src_pos: &Loc, type_repr: &Type) -> Term {
+    match value {
+        Value::Int(value) => Term { value: TermKind::Int(value), src_pos: src_pos.clone(), type_repr: type_repr.clone() },
+        Value::Text(text) => Term { value: TermKind::Text(text), src_pos: src_pos.clone(), type_repr: type_repr.clone() },
+        Value::List(elements) => Term {
+            value: TermKind::List(elements.into_iter().map(|element| quote(element, src_pos, &Type::Any)).collect()),
+            src_pos: src_pos.clone(),
+            type_repr: type_repr.clone(),
+        },
+        Value::Pair(elements) => Term {
+            value: TermKind::Pair(elements.into_iter().map(|element| quote(element, src_pos, &Type::Any)).collect()),
+            src_pos: src_pos.clone(),
+            type_repr: type_repr.clone(),
+        },
+        Value::Constructor(reference, arguments) => {
+            let head = Term {
+                value: TermKind::Var(reference),
+                src_pos: src_pos.clone(),
+                type_repr: type_repr.clone(),
+            };
+            arguments.into_iter().fold(head, |function, argument| Term {
+                value: TermKind::App(Box::new(function), Box::new(quote(argument, src_pos, &Type::Any))),
+                src_pos: src_pos.clone(),
+                type_repr: type_repr.clone(),
+            })
+        }
+        Value::Closure(..) => unreachable!("closures are never quoted, see simplify_term_with"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_addition_subtraction_and_multiplication() {
+        assert!(matches!(eval_primitive("+", &[Value::Int(1), Value::Int(2)]), Some(Value::Int(3))));
+        assert!(matches!(eval_primitive("-", &[Value::Int(5), Value::Int(2)]), Some(Value::Int(3))));
+        assert!(matches!(eval_primitive("*", &[Value::Int(3), Value::Int(4)]), Some(Value::Int(12))));
+    }
+
+    #[test]
+    fn division_and_modulo_by_zero_are_left_unevaluated() {
+        assert!(eval_primitive("/", &[Value::Int(1), Value::Int(0)]).is_none());
+        assert!(eval_primitive("%", &[Value::Int(1), Value::Int(0)]).is_none());
+    }
+
+    #[test]
+    fn folds_comparisons_to_ints() {
+        assert!(matches!(eval_primitive("<", &[Value::Int(1), Value::Int(2)]), Some(Value::Int(1))));
+        assert!(matches!(eval_primitive(">=", &[Value::Int(1), Value::Int(2)]), Some(Value::Int(0))));
+        assert!(matches!(eval_primitive("==", &[Value::Int(2), Value::Int(2)]), Some(Value::Int(1))));
+    }
+
+    #[test]
+    fn unknown_names_and_arities_are_not_folded() {
+        assert!(eval_primitive("unknown_fn", &[Value::Int(1), Value::Int(2)]).is_none());
+        assert!(eval_primitive("+", &[Value::Int(1)]).is_none());
+        assert!(eval_primitive("+", &[Value::List(vec![]), Value::Int(1)]).is_none());
+    }
+}
@@ -0,0 +1,414 @@
+//! Maranget's usefulness algorithm, used to check [super::CaseTree]s built from
+//! `match` expressions for non-exhaustiveness and redundant arms.
+//!
+//! This operates one level below [super::CaseTree]: instead of walking the
+//! already-compiled decision tree, it works over the pattern matrix that the
+//! tree was compiled from, since usefulness is a property of the *source*
+//! arms, not of any particular compilation strategy.
+//!
+//! Everything here is generic over the constructor identifier type `C`
+//! (instantiated with [crate::abstr::Reference] by the lowering code)
+//! purely so the algorithm itself &mdash; in particular the
+//! complete-signature check &mdash; can be exercised with plain test
+//! fixtures instead of needing a real `Reference`.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// A single pattern in a match arm, as seen by the usefulness checker.
+///
+/// This mirrors [super::Condition] plus a wildcard case and integer literals,
+/// which need special handling because they have no finite signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern<C> {
+    /// `_` or a bound variable: matches anything.
+    Wildcard,
+
+    /// `C(p0, p1, ...)`, a constructor applied to its sub-patterns.
+    Constructor(C, Vec<Pattern<C>>),
+
+    /// `(p0, p1, ...)`, a fixed-arity tuple.
+    Tuple(Vec<Pattern<C>>),
+
+    /// An integer literal. Integers have an effectively infinite
+    /// constructor set, so a column of integer literals is never complete.
+    Int(i64),
+}
+
+/// A row of the pattern matrix: one pattern per scrutinee occurrence.
+pub type Row<C> = Vec<Pattern<C>>;
+
+/// The full set of constructors belonging to one sum type, each paired
+/// with its arity, keyed by every constructor that belongs to it. This is
+/// what [is_complete_signature] needs to tell "every constructor of this
+/// type is covered" apart from "this constructor column just happens to be
+/// incomplete".
+#[derive(Debug, Clone)]
+pub struct Signature<C> {
+    siblings: HashMap<C, Vec<(C, usize)>>,
+}
+
+impl<C> Default for Signature<C> {
+    fn default() -> Self {
+        Signature { siblings: HashMap::new() }
+    }
+}
+
+impl<C: Clone + Eq + Hash> Signature<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares that `variants` are *exactly* the constructors of one sum
+    /// type (name paired with arity), so each is recorded as a sibling of
+    /// every other, itself included.
+    pub fn declare(&mut self, variants: Vec<(C, usize)>) {
+        for (name, _) in &variants {
+            self.siblings.insert(name.clone(), variants.clone());
+        }
+    }
+
+    /// The full constructor list of the sum type `constructor` belongs to,
+    /// or `None` if `constructor` was never declared (in which case the
+    /// checker falls back to a conservative "never complete").
+    fn siblings_of(&self, constructor: &C) -> Option<&[(C, usize)]> {
+        self.siblings.get(constructor).map(Vec::as_slice)
+    }
+}
+
+/// The head constructor of a pattern, used to build specialized matrices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Head<C> {
+    Constructor(C, usize),
+    Tuple(usize),
+    Int(i64),
+}
+
+fn head_of<C: Clone>(pattern: &Pattern<C>) -> Option<Head<C>> {
+    match pattern {
+        Pattern::Wildcard => None,
+        Pattern::Constructor(name, args) => Some(Head::Constructor(name.clone(), args.len())),
+        Pattern::Tuple(elements) => Some(Head::Tuple(elements.len())),
+        Pattern::Int(value) => Some(Head::Int(*value)),
+    }
+}
+
+/// Expands a wildcard into `arity` wildcards, used when specializing rows
+/// whose first pattern is a wildcard, and to build all-wildcard queries.
+fn wildcards<C>(arity: usize) -> Vec<Pattern<C>> {
+    (0..arity).map(|_| Pattern::Wildcard).collect()
+}
+
+/// `S(c, P)`: keep the rows of `P` whose first pattern is `c` or a wildcard,
+/// replacing the first column with `c`'s sub-patterns (or fresh wildcards).
+fn specialize<C: Clone + PartialEq>(head: &Head<C>, rows: &[Row<C>]) -> Vec<Row<C>> {
+    let arity = match head {
+        Head::Constructor(_, arity) => *arity,
+        Head::Tuple(arity) => *arity,
+        Head::Int(_) => 0,
+    };
+
+    rows.iter()
+        .filter_map(|row| {
+            let (first, rest) = row.split_first()?;
+            match first {
+                Pattern::Wildcard => {
+                    let mut specialized = wildcards(arity);
+                    specialized.extend_from_slice(rest);
+                    Some(specialized)
+                }
+                Pattern::Constructor(_, args) if head_of(first) == Some(head.clone()) => {
+                    let mut specialized = args.clone();
+                    specialized.extend_from_slice(rest);
+                    Some(specialized)
+                }
+                Pattern::Tuple(elements) if head_of(first) == Some(head.clone()) => {
+                    let mut specialized = elements.clone();
+                    specialized.extend_from_slice(rest);
+                    Some(specialized)
+                }
+                Pattern::Int(_) if head_of(first) == Some(head.clone()) => Some(rest.to_vec()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// `D(P)`: drop the rows with a constructor in the first column, and drop the
+/// first column from the rows that remain (all of them wildcards).
+fn default_matrix<C>(rows: &[Row<C>]) -> Vec<Row<C>> {
+    rows.iter()
+        .filter_map(|row| {
+            let (first, rest) = row.split_first()?;
+            matches!(first, Pattern::Wildcard).then(|| rest.to_vec())
+        })
+        .collect()
+}
+
+/// The distinct heads appearing in the first column of `rows`, in order of
+/// first appearance; wildcard rows contribute nothing.
+fn column_heads<C: Clone + Eq + Hash>(rows: &[Row<C>]) -> Vec<Head<C>> {
+    let mut heads = Vec::new();
+    for row in rows {
+        if let Some(head) = row.first().and_then(head_of) {
+            if !heads.contains(&head) {
+                heads.push(head);
+            }
+        }
+    }
+    heads
+}
+
+/// Whether `heads` (the constructors appearing in the first column of a
+/// matrix) form a complete signature of the type they inhabit.
+///
+/// Tuples are a single-constructor signature of fixed arity, so a tuple
+/// column is always complete as soon as it's non-empty. Integers have an
+/// unbounded constructor set, so an integer column is never complete.
+/// Ordinary constructors are complete when `signature` knows every sibling
+/// of the constructors actually seen in the column and all of them are
+/// present; if the column's constructor was never declared in `signature`
+/// we conservatively say it's incomplete.
+fn is_complete_signature<C: Clone + Eq + Hash>(heads: &[Head<C>], signature: &Signature<C>) -> bool {
+    match heads.first() {
+        Some(Head::Tuple(_)) => true,
+        Some(Head::Int(_)) => false,
+        Some(Head::Constructor(constructor, _)) => match signature.siblings_of(constructor) {
+            Some(siblings) => {
+                let seen: HashSet<&C> = heads
+                    .iter()
+                    .filter_map(|head| match head {
+                        Head::Constructor(name, _) => Some(name),
+                        _ => None,
+                    })
+                    .collect();
+                siblings.len() == seen.len() && siblings.iter().all(|(name, _)| seen.contains(name))
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// `U(P, q)`: is pattern vector `q` useful against matrix `P`?
+///
+/// Returns `Some(witness)` when `q` is useful, with `witness` being a
+/// completed pattern vector (in `q`'s shape) that `P` doesn't cover. Returns
+/// `None` when `q` is not useful, i.e. every value it matches is already
+/// matched by some row of `P`.
+pub fn is_useful<C: Clone + Eq + Hash>(rows: &[Row<C>], query: &[Pattern<C>], signature: &Signature<C>) -> Option<Vec<Pattern<C>>> {
+    let Some((first, rest)) = query.split_first() else {
+        // No columns left: q is useful iff P has no rows.
+        return rows.is_empty().then(Vec::new);
+    };
+
+    match first {
+        Pattern::Constructor(name, args) => {
+            let head = Head::Constructor(name.clone(), args.len());
+            let specialized_rows = specialize(&head, rows);
+            let mut specialized_query = args.clone();
+            specialized_query.extend_from_slice(rest);
+            is_useful(&specialized_rows, &specialized_query, signature).map(|mut witness| {
+                let sub_args = witness.drain(..args.len()).collect();
+                let mut result = vec![Pattern::Constructor(name.clone(), sub_args)];
+                result.extend(witness);
+                result
+            })
+        }
+        Pattern::Tuple(elements) => {
+            let head = Head::Tuple(elements.len());
+            let specialized_rows = specialize(&head, rows);
+            let mut specialized_query = elements.clone();
+            specialized_query.extend_from_slice(rest);
+            is_useful(&specialized_rows, &specialized_query, signature).map(|mut witness| {
+                let sub_elements = witness.drain(..elements.len()).collect();
+                let mut result = vec![Pattern::Tuple(sub_elements)];
+                result.extend(witness);
+                result
+            })
+        }
+        Pattern::Int(value) => {
+            // Specialize exactly like a constructor/tuple: keep rows whose
+            // literal matches (plus wildcard rows), drop the rest. Integers
+            // only ever skip specialization on the *wildcard* query path
+            // below, where there's no single literal to specialize on.
+            let head = Head::Int(*value);
+            let specialized_rows = specialize(&head, rows);
+            is_useful(&specialized_rows, rest, signature).map(|mut witness| {
+                witness.insert(0, first.clone());
+                witness
+            })
+        }
+        Pattern::Wildcard => {
+            let heads = column_heads(rows);
+            if is_complete_signature(&heads, signature) {
+                // Try every constructor of the complete signature; q is
+                // useful iff it's useful for at least one of them.
+                for head in &heads {
+                    let arity = match head {
+                        Head::Tuple(arity) => *arity,
+                        Head::Constructor(_, arity) => *arity,
+                        Head::Int(_) => 0,
+                    };
+                    let specialized_rows = specialize(head, rows);
+                    let mut specialized_query = wildcards(arity);
+                    specialized_query.extend_from_slice(rest);
+                    if let Some(mut witness) = is_useful(&specialized_rows, &specialized_query, signature) {
+                        let sub = witness.drain(..arity).collect();
+                        let reconstructed = match head {
+                            Head::Tuple(_) => Pattern::Tuple(sub),
+                            Head::Constructor(name, _) => Pattern::Constructor(name.clone(), sub),
+                            Head::Int(value) => Pattern::Int(*value),
+                        };
+                        let mut result = vec![reconstructed];
+                        result.extend(witness);
+                        return Some(result);
+                    }
+                }
+                None
+            } else {
+                // A declared constructor absent from the column makes a more
+                // precise witness than `_`; undeclared, tuple and integer
+                // columns (or an empty one) fall back to `_`.
+                let missing = match heads.first() {
+                    Some(Head::Constructor(constructor, _)) => signature
+                        .siblings_of(constructor)
+                        .and_then(|siblings| {
+                            siblings.iter().find(|(name, _)| {
+                                !heads.iter().any(|head| matches!(head, Head::Constructor(seen, _) if seen == name))
+                            })
+                        })
+                        .map(|(name, arity)| Pattern::Constructor(name.clone(), wildcards(*arity))),
+                    _ => None,
+                };
+                is_useful(&default_matrix(rows), rest, signature).map(|mut witness| {
+                    witness.insert(0, missing.unwrap_or(Pattern::Wildcard));
+                    witness
+                })
+            }
+        }
+    }
+}
+
+/// Checks whether `rows` (the patterns of every arm of a match, in order)
+/// is exhaustive. Returns a witness row describing a value that isn't
+/// covered by any arm, or `None` if the match is exhaustive.
+pub fn check_exhaustiveness<C: Clone + Eq + Hash>(rows: &[Row<C>], arity: usize, signature: &Signature<C>) -> Option<Vec<Pattern<C>>> {
+    let wildcard_query = wildcards(arity);
+    is_useful(rows, &wildcard_query, signature)
+}
+
+/// Returns the indices of the arms in `rows` that are redundant, i.e. every
+/// value they match is already matched by some earlier arm.
+pub fn find_redundant_rows<C: Clone + Eq + Hash>(rows: &[Row<C>], signature: &Signature<C>) -> Vec<usize> {
+    let mut redundant = Vec::new();
+    for i in 0..rows.len() {
+        if is_useful(&rows[..i], &rows[i], signature).is_none() {
+            redundant.push(i);
+        }
+    }
+    redundant
+}
+
+/// Renders a witness row as a human-readable missing pattern, e.g. `Cons(_, Nil)`.
+pub fn render_witness<C: std::fmt::Display>(witness: &[Pattern<C>]) -> String {
+    fn render_one<C: std::fmt::Display>(pattern: &Pattern<C>) -> String {
+        match pattern {
+            Pattern::Wildcard => "_".into(),
+            Pattern::Int(_) => "_".into(),
+            Pattern::Tuple(elements) => {
+                format!("({})", elements.iter().map(render_one).collect::<Vec<_>>().join(", "))
+            }
+            Pattern::Constructor(name, args) if args.is_empty() => name.to_string(),
+            Pattern::Constructor(name, args) => {
+                format!("{}({})", name, args.iter().map(render_one).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+
+    witness.iter().map(render_one).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These tests use plain `&'static str`s as the constructor identifier
+    // (`C`) instead of `crate::abstr::Reference`, since `Reference` is
+    // defined outside this snapshot of the crate and can't be constructed
+    // here. The algorithm itself doesn't care what `C` is (it only needs
+    // `Clone + Eq + Hash`), so this exercises the exact same code path
+    // `LoweringCtx::check_match_usefulness` drives with real `Reference`s.
+
+    fn list_signature() -> Signature<&'static str> {
+        let mut signature = Signature::new();
+        signature.declare(vec![("Cons", 2), ("Nil", 0)]);
+        signature
+    }
+
+    #[test]
+    fn exhaustive_sum_type_match_is_not_flagged_without_a_wildcard() {
+        // match x with | Cons(_, _) -> .. | Nil -> ..
+        let rows: Vec<Row<&'static str>> = vec![
+            vec![Pattern::Constructor("Cons", vec![Pattern::Wildcard, Pattern::Wildcard])],
+            vec![Pattern::Constructor("Nil", vec![])],
+        ];
+
+        assert_eq!(check_exhaustiveness(&rows, 1, &list_signature()), None);
+    }
+
+    #[test]
+    fn missing_constructor_is_reported_as_non_exhaustive() {
+        // match x with | Cons(_, _) -> ..   (missing `Nil`)
+        let rows: Vec<Row<&'static str>> = vec![vec![Pattern::Constructor(
+            "Cons",
+            vec![Pattern::Wildcard, Pattern::Wildcard],
+        )]];
+
+        let witness = check_exhaustiveness(&rows, 1, &list_signature()).expect("should be non-exhaustive");
+        assert_eq!(render_witness(&witness), "Nil");
+    }
+
+    #[test]
+    fn undeclared_constructor_column_falls_back_to_conservative_default() {
+        // No `Signature::declare` call for this type: we don't know its
+        // siblings, so the conservative "never complete" behavior applies,
+        // rather than panicking or guessing.
+        let rows: Vec<Row<&'static str>> = vec![
+            vec![Pattern::Constructor("Cons", vec![Pattern::Wildcard, Pattern::Wildcard])],
+            vec![Pattern::Constructor("Nil", vec![])],
+        ];
+
+        assert!(check_exhaustiveness(&rows, 1, &Signature::new()).is_some());
+    }
+
+    #[test]
+    fn duplicate_int_literal_arm_is_redundant() {
+        // match n with | 1 -> "a" | 1 -> "b" | _ -> "c"
+        let rows: Vec<Row<&'static str>> = vec![
+            vec![Pattern::Int(1)],
+            vec![Pattern::Int(1)],
+            vec![Pattern::Wildcard],
+        ];
+
+        assert_eq!(find_redundant_rows(&rows, &Signature::new()), vec![1]);
+    }
+
+    #[test]
+    fn distinct_int_literal_arms_are_all_reachable() {
+        let rows: Vec<Row<&'static str>> = vec![
+            vec![Pattern::Int(1)],
+            vec![Pattern::Int(2)],
+            vec![Pattern::Wildcard],
+        ];
+
+        assert!(find_redundant_rows(&rows, &Signature::new()).is_empty());
+    }
+
+    #[test]
+    fn int_match_without_wildcard_is_non_exhaustive() {
+        let rows: Vec<Row<&'static str>> = vec![vec![Pattern::Int(1)], vec![Pattern::Int(2)]];
+
+        assert!(check_exhaustiveness(&rows, 1, &Signature::new()).is_some());
+    }
+}